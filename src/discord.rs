@@ -0,0 +1,186 @@
+//! Discord voice-channel mode: join a call and run the Whisper→GPT-4o→TTS pipeline
+//! over it instead of recording through ffmpeg and playing back through mpv.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use serenity::all::{Context, EventHandler, GatewayIntents, GuildId, Ready};
+use serenity::async_trait;
+use songbird::events::{CoreEvent, Event, EventContext, EventHandler as VoiceEventHandler};
+use songbird::{Call, SerenityInit};
+use tokio::sync::Mutex;
+
+use crate::CONFIG;
+
+/// Flush a user's buffer once this many consecutive 20ms ticks arrive without their audio.
+const SILENCE_TICKS: u32 = 25; // ~500ms
+
+struct Handler {
+	client: reqwest::Client,
+}
+
+#[async_trait]
+impl EventHandler for Handler {
+	async fn ready(&self, ctx: Context, ready: Ready) {
+		println!("Connected as {}", ready.user.name);
+
+		let Some(manager) = songbird::get(&ctx).await else {
+			eprintln!("Error: songbird not registered");
+			return;
+		};
+
+		let call = match manager.join(GuildId::new(CONFIG.guild_id), CONFIG.channel_id).await {
+			Ok(call) => call,
+			Err(e)   => { eprintln!("Error: failed to join voice channel: {e}"); return; }
+		};
+
+		let receiver = Receiver::new(Arc::clone(&call), self.client.clone());
+		let mut call = call.lock().await;
+		call.add_global_event(CoreEvent::VoiceTick.into(),          receiver.clone());
+		call.add_global_event(CoreEvent::SpeakingStateUpdate.into(), receiver);
+
+		println!("Listening in channel {}", CONFIG.channel_id);
+	}
+}
+
+#[derive(Clone)]
+struct Receiver {
+	call:     Arc<Mutex<Call>>,
+	client:   reqwest::Client,
+	// Per-speaker PCM accumulator plus the count of ticks since we last heard them.
+	buffers:  Arc<Mutex<HashMap<u32, (Vec<i16>, u32)>>>,
+	messages: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl Receiver {
+	fn new(call: Arc<Mutex<Call>>, client: reqwest::Client) -> Self {
+		Self {
+			call,
+			client,
+			buffers:  Arc::new(Mutex::new(HashMap::new())),
+			messages: Arc::new(Mutex::new(VecDeque::new())),
+		}
+	}
+}
+
+#[async_trait]
+impl VoiceEventHandler for Receiver {
+	async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+		if let EventContext::VoiceTick(tick) = ctx {
+			let mut buffers = self.buffers.lock().await;
+
+			// Append this tick's decoded audio and reset the silence counter for anyone speaking.
+			for (ssrc, data) in &tick.speaking {
+				if let Some(pcm) = &data.decoded_voice {
+					let entry = buffers.entry(*ssrc).or_insert_with(|| (Vec::new(), 0));
+					entry.0.extend_from_slice(pcm);
+					entry.1 = 0;
+				}
+			}
+
+			// Anyone who has gone quiet long enough has finished an utterance.
+			let done: Vec<u32> = buffers.iter_mut()
+				.filter(|(ssrc, (pcm, _))| !pcm.is_empty() && !tick.speaking.contains_key(ssrc))
+				.filter_map(|(ssrc, (_, idle))| { *idle += 1; (*idle >= SILENCE_TICKS).then_some(*ssrc) })
+				.collect();
+
+			for ssrc in done {
+				let (pcm, _) = buffers.remove(&ssrc).unwrap();
+				let (call, client, messages) = (Arc::clone(&self.call), self.client.clone(), Arc::clone(&self.messages));
+				tokio::spawn(async move {
+					if let Err(e) = handle_utterance(&client, &call, &messages, ssrc, pcm).await {
+						eprintln!("Error: {e}");
+					}
+				});
+			}
+		}
+
+		None
+	}
+}
+
+/// Encode a single captured utterance, run it through the pipeline, and speak the reply back into the call.
+async fn handle_utterance(
+	client: &reqwest::Client,
+	call: &Arc<Mutex<Call>>,
+	messages: &Arc<Mutex<VecDeque<String>>>,
+	ssrc: u32,
+	pcm: Vec<i16>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+	// Per-ssrc temp paths so concurrent speakers don't clobber each other's audio/transcription.
+	let path = std::env::temp_dir().join(format!("chatgpt_slop_utterance_{ssrc}.wav"));
+	write_wav(&path, &pcm)?;
+
+	let text = crate::transcribe(client, path.to_str().unwrap()).await?;
+	if text.trim().is_empty() { return Ok(()); }
+	println!("Transcription: {text}");
+
+	let body = {
+		let mut messages = messages.lock().await;
+		messages.push_back(serde_json::json!({ "role": "user", "content": crate::escape_json(&text) }).to_string());
+		crate::trim_messages(&mut messages);
+		format!(r#"{{ "model": "gpt-4o", "messages": [{}{} {}] }}"#,
+			CONFIG.prompt, if !CONFIG.prompt.is_empty() { "," } else { "" },
+			messages.iter().enumerate().fold(String::with_capacity(100),
+				|acc, (i, s)| if i == messages.len()-1 { acc + s } else { acc + s + "," }))
+	};
+
+	let reply = crate::chat(client, body).await?;
+	println!("Response: {reply}");
+
+	{
+		let mut messages = messages.lock().await;
+		messages.push_back(serde_json::json!({ "role": "assistant", "content": crate::escape_json(&reply) }).to_string());
+		crate::trim_messages(&mut messages);
+	}
+
+	let (style, body) = crate::split_style(&reply);
+	let audio = crate::synthesize(client, style, body.to_string()).await?;
+	let out = std::env::temp_dir().join(format!("chatgpt_slop_reply_{ssrc}.mp3"));
+	std::fs::write(&out, &audio)?;
+
+	let input = songbird::input::File::new(out);
+	call.lock().await.play_input(input.into());
+	Ok(())
+}
+
+/// Entry point for `mode = "discord"`: wire up serenity + songbird and block on the gateway.
+pub async fn run(http: reqwest::Client) -> Result<(), Box<dyn std::error::Error>> {
+	let intents = GatewayIntents::GUILDS | GatewayIntents::GUILD_VOICE_STATES;
+	let mut client = serenity::Client::builder(&*CONFIG.discord_token, intents)
+		.event_handler(Handler { client: http })
+		.register_songbird()
+		.await?;
+
+	client.start().await?;
+	Ok(())
+}
+
+/// Write interleaved 48kHz stereo 16-bit PCM out as a minimal WAV container.
+fn write_wav(path: &std::path::Path, pcm: &[i16]) -> std::io::Result<()> {
+	use std::io::Write;
+
+	const CHANNELS:    u16 = 2;
+	const SAMPLE_RATE: u32 = 48_000;
+	const BITS:        u16 = 16;
+
+	let data_len  = (pcm.len() * 2) as u32;
+	let byte_rate = SAMPLE_RATE * CHANNELS as u32 * (BITS / 8) as u32;
+
+	let mut f = std::io::BufWriter::new(std::fs::File::create(path)?);
+	f.write_all(b"RIFF")?;
+	f.write_all(&(36 + data_len).to_le_bytes())?;
+	f.write_all(b"WAVE")?;
+	f.write_all(b"fmt ")?;
+	f.write_all(&16u32.to_le_bytes())?;
+	f.write_all(&1u16.to_le_bytes())?; // PCM
+	f.write_all(&CHANNELS.to_le_bytes())?;
+	f.write_all(&SAMPLE_RATE.to_le_bytes())?;
+	f.write_all(&byte_rate.to_le_bytes())?;
+	f.write_all(&(CHANNELS * BITS / 8).to_le_bytes())?;
+	f.write_all(&BITS.to_le_bytes())?;
+	f.write_all(b"data")?;
+	f.write_all(&data_len.to_le_bytes())?;
+	for s in pcm { f.write_all(&s.to_le_bytes())?; }
+	f.flush()
+}
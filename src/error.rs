@@ -0,0 +1,31 @@
+//! Crate error type. Config loading and each push-to-talk turn return one of these instead of
+//! panicking, so a transient HTTP hiccup or malformed response is recoverable per-iteration.
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+	#[error("config: {0}")]
+	Config(String),
+
+	#[error(transparent)]
+	Io(#[from] std::io::Error),
+
+	#[error(transparent)]
+	Http(#[from] reqwest::Error),
+
+	#[error("http {status}: {body}")]
+	Status { status: reqwest::StatusCode, body: String },
+
+	#[error("unexpected response shape: missing `{0}`")]
+	Shape(&'static str),
+
+	#[error("child process `{0}` failed")]
+	Child(String),
+}
+
+impl From<std::sync::mpsc::RecvError> for Error {
+	fn from(_: std::sync::mpsc::RecvError) -> Self {
+		Error::Child("key listener".into())
+	}
+}
@@ -6,6 +6,11 @@ use std::num::NonZero;
 use rdev::{Event, EventType, Key};
 use serde::Deserialize;
 
+mod discord;
+mod error;
+
+use error::Error;
+
 #[derive(Deserialize, Default)]
 struct Config {
 	#[serde(deserialize_with = "openai_key")]
@@ -18,11 +23,29 @@ struct Config {
 	#[serde(deserialize_with = "prompt")]
 	prompt:     Box<str>,
 	audio_file: Box<str>,
-	msg_limit:  usize,
+	token_limit: usize,
 	#[serde(deserialize_with = "device")]
 	device:     Box<str>,
 	backend:    Box<str>,
 	#[serde(default)]
+	tts_backend: Box<str>,
+	#[serde(default)]
+	transcript_format: Box<str>,
+	#[serde(default)]
+	stt_backend:   Box<str>,
+	#[serde(default)]
+	whisper_bin:   Box<str>,
+	#[serde(default)]
+	whisper_model: Box<str>,
+	#[serde(default)]
+	mode:       Box<str>,
+	#[serde(default)]
+	discord_token: Box<str>,
+	#[serde(default)]
+	guild_id:   u64,
+	#[serde(default)]
+	channel_id: u64,
+	#[serde(default)]
 	keycode:    Option<NonZero<u32>>,
 }
 
@@ -50,17 +73,26 @@ fn device<'de, D: serde::Deserializer<'de>>(de: D) -> Result<Box<str>, D::Error>
 }
 
 const CONFIG_PATH: &str = "config.toml";
-static CONFIG: LazyLock<Config> = LazyLock::new(||
-	toml::from_str(&std::fs::read_to_string(CONFIG_PATH).unwrap()).unwrap_or_else(|e| {
-		eprintln!("Error reading config: {e}");
-		std::process::exit(1);
-	}));
+static CONFIG: LazyLock<Config> = LazyLock::new(|| load_config().unwrap_or_else(|e| {
+	eprintln!("{e}");
+	std::process::exit(1);
+}));
+
+fn load_config() -> Result<Config, Error> {
+	let s = std::fs::read_to_string(CONFIG_PATH)?;
+	toml::from_str(&s).map_err(|e| Error::Config(e.to_string()))
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-	let mut messages: VecDeque<String> = VecDeque::new();
 	let client = reqwest::Client::new();
 
+	if &*CONFIG.mode == "discord" {
+		return discord::run(client).await;
+	}
+
+	let mut messages: VecDeque<String> = VecDeque::new();
+
 	let (tx, rx) = std::sync::mpsc::channel();
 
 	const DEFAULT_KEY: Key = Key::F1;
@@ -71,84 +103,290 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
 	tokio::spawn(async move {
 		rdev::listen(move |e|
-			if let Event { event_type: EventType::KeyPress(k), .. } = e 
-				{ if k == key { tx.send(()).unwrap(); } }).unwrap()
+			if let Event { event_type: EventType::KeyPress(k), .. } = e
+				{ if k == key { let _ = tx.send(()); } }).unwrap()
 	});
 
 	loop {
 		println!("Press key to start");
-		rx.recv().unwrap();
+		if rx.recv().is_err() { break Ok(()); }
 
-		let mut cmd = std::process::Command::new("ffmpeg")
+		// Record outside the fallible body so the child is in scope to kill on any later error.
+		let mut cmd = match std::process::Command::new("ffmpeg")
 			.args(["-y", "-loglevel", "error", "-f", &CONFIG.backend, "-i", &CONFIG.device, &CONFIG.audio_file])
 			.stdin(std::process::Stdio::piped())
-			.spawn()?;
-
-		println!("Recording..");
-		rx.recv().unwrap();
-
-		cmd.stdin.as_mut().unwrap().write_all(b"q")?; // lol
-		cmd.wait().unwrap();
-
-		let serde_json::Value::String(resp) = 
-			check_err(client.post("https://api.openai.com/v1/audio/transcriptions")
-				.header("Authorization", &*CONFIG.openai_key)
-				.multipart(reqwest::multipart::Form::new()
-					.file("file", &*CONFIG.audio_file).await?
-					.text("model", "whisper-1"))
-				.send().await?).await
-				.json::<serde_json::Value>().await?
-				.get_mut("text").unwrap().take()
-			else { panic!("Invalid response") };
-
-		println!("Transcription: {resp}");
-		messages.push_back(serde_json::json!({ "role": "user", "content": escape_json(&resp) }).to_string());
-
-		let serde_json::Value::String(resp) = 
-			check_err(client.post("https://api.openai.com/v1/chat/completions")
-				.header("Authorization", &*CONFIG.openai_key)
-				.header("Content-Type", "application/json")
-				.body(format!(r#"{{ "model": "gpt-4o", "messages": [{}{} {}] }}"#,
-					CONFIG.prompt, if !CONFIG.prompt.is_empty() { "," } else { "" },
-					messages.iter().enumerate().fold(String::with_capacity(100), 
-						|acc, (i, s)| if i == messages.len()-1 { acc + s } else { acc + s + "," })))
-				.send().await?).await
-				.json::<serde_json::Value>().await?
-				.get_mut("choices").unwrap().take()
-				.get_mut(0).unwrap().take()
-				.get_mut("message").unwrap().take()
-				.get_mut("content").unwrap().take()
-			else { panic!("Invalid response") };
-
-		println!("Response: {resp}");
-		if messages.len() >= CONFIG.msg_limit { messages.pop_front(); }
-
-		let resp = check_err(client.post(format!("https://{}.tts.speech.microsoft.com/cognitiveservices/v1", &*CONFIG.azure_region))
+			.spawn()
+		{
+			Ok(cmd) => cmd,
+			Err(e)  => { eprintln!("{}", Error::from(e)); continue; }
+		};
+
+		if let Err(e) = turn(&client, &mut messages, &rx, &mut cmd).await {
+			eprintln!("{e}");
+		}
+
+		// Whether the turn succeeded or bailed, make sure the recorder isn't left running.
+		let _ = cmd.kill();
+	}
+}
+
+/// One recording → transcription → chat → TTS cycle. Any failure returns an [`Error`] so the
+/// main loop can report it and return to the "Press key to start" state instead of aborting.
+async fn turn(
+	client: &reqwest::Client,
+	messages: &mut VecDeque<String>,
+	rx: &std::sync::mpsc::Receiver<()>,
+	cmd: &mut std::process::Child,
+) -> Result<(), Error> {
+	println!("Recording..");
+	rx.recv()?;
+
+	cmd.stdin.as_mut().ok_or(Error::Child("ffmpeg stdin".into()))?.write_all(b"q")?; // lol
+	cmd.wait()?;
+
+	let resp = transcribe(client, &CONFIG.audio_file).await?;
+
+	println!("Transcription: {resp}");
+	messages.push_back(serde_json::json!({ "role": "user", "content": escape_json(&resp) }).to_string());
+
+	let mut stream =
+		check_err(client.post("https://api.openai.com/v1/chat/completions")
+			.header("Authorization", &*CONFIG.openai_key)
+			.header("Content-Type", "application/json")
+			.body(format!(r#"{{ "model": "gpt-4o", "stream": true, "messages": [{}{} {}] }}"#,
+				CONFIG.prompt, if !CONFIG.prompt.is_empty() { "," } else { "" },
+				messages.iter().enumerate().fold(String::with_capacity(100),
+					|acc, (i, s)| if i == messages.len()-1 { acc + s } else { acc + s + "," })))
+			.send().await?).await?;
+
+	// One long-lived mpv pipe per reply: sentence N plays while N+1 is still synthesizing.
+	let mut mpv = std::process::Command::new("mpv")
+		.args(["-", "--no-terminal"])
+		.stdin(std::process::Stdio::piped())
+		.spawn()?;
+	let mut sink = mpv.stdin.take().ok_or(Error::Child("mpv stdin".into()))?;
+
+	let mut pending: VecDeque<SynthTask> = VecDeque::new();
+	let mut reply    = String::new();
+	let mut sentence = String::new();
+	let mut buf      = String::new();
+	// The azure `:style …` express-as marker only appears once at the head of the reply; detect it
+	// on the first flushed sentence and apply the same style to every subsequent one.
+	let mut style:  Option<String> = None;
+	let mut marked = false;
+
+	print!("Response: ");
+	std::io::stdout().flush()?;
+	'stream: while let Some(chunk) = stream.chunk().await? {
+		buf.push_str(&String::from_utf8_lossy(&chunk));
+		while let Some(nl) = buf.find('\n') {
+			let line: String = buf.drain(..=nl).collect();
+			let Some(data) = line.trim().strip_prefix("data: ") else { continue };
+			if data == "[DONE]" { break 'stream; }
+
+			let Ok(event) = serde_json::from_str::<serde_json::Value>(data) else { continue };
+			let Some(delta) = event["choices"][0]["delta"]["content"].as_str() else { continue };
+
+			print!("{delta}");
+			std::io::stdout().flush()?;
+			reply.push_str(delta);
+			sentence.push_str(delta);
+
+			if delta.contains(['.', '!', '?', '\n']) {
+				let chunk = take_style(std::mem::take(&mut sentence), &mut style, &mut marked);
+				pending.push_back(spawn_synth(client, style.clone(), chunk));
+				// Keep a shallow backlog so synthesis overlaps playback without unbounded memory.
+				while pending.len() > 2 { drain_one(&mut pending, &mut sink).await?; }
+			}
+		}
+	}
+	println!();
+
+	if !sentence.trim().is_empty() {
+		let chunk = take_style(std::mem::take(&mut sentence), &mut style, &mut marked);
+		pending.push_back(spawn_synth(client, style.clone(), chunk));
+	}
+	while drain_one(&mut pending, &mut sink).await?.is_some() {}
+
+	messages.push_back(serde_json::json!({ "role": "assistant", "content": escape_json(&reply) }).to_string());
+	trim_messages(messages);
+	Ok(())
+}
+
+/// Drop the oldest turns until the running char/4 token estimate fits under `token_limit`.
+/// The system `prompt` lives outside this deque and so is never evicted.
+fn trim_messages(messages: &mut VecDeque<String>) {
+	let mut total: usize = messages.iter().map(|m| m.chars().count() / 4).sum();
+	while total > CONFIG.token_limit {
+		let Some(front) = messages.pop_front() else { break };
+		total -= front.chars().count() / 4;
+	}
+}
+
+type SynthTask = tokio::task::JoinHandle<Result<Vec<u8>, Error>>;
+
+fn spawn_synth(client: &reqwest::Client, style: Option<String>, text: String) -> SynthTask {
+	let client = client.clone();
+	tokio::spawn(async move { synthesize(&client, style.as_deref(), text).await })
+}
+
+/// Split an optional leading `:style …` express-as marker off a reply.
+fn split_style(text: &str) -> (Option<&str>, &str) {
+	match text.strip_prefix(':') {
+		Some(rest) => match rest.find(' ') {
+			Some(pos) => (Some(&rest[..pos]), &rest[pos + 1..]),
+			None      => (Some(rest), ""),
+		},
+		None => (None, text),
+	}
+}
+
+/// Strip the style marker from the first sentence, recording it in `style` for reuse on the rest.
+/// Once detected (or ruled out) on the first sentence, `marked` latches so later sentences are
+/// passed through verbatim.
+fn take_style(chunk: String, style: &mut Option<String>, marked: &mut bool) -> String {
+	if *marked { return chunk; }
+	*marked = true;
+	let (st, body) = split_style(&chunk);
+	*style = st.map(str::to_string);
+	body.to_string()
+}
+
+/// Await the oldest pending synthesis and pipe its audio into mpv; `None` when the queue is empty.
+async fn drain_one(pending: &mut VecDeque<SynthTask>, sink: &mut std::process::ChildStdin) -> Result<Option<()>, Error> {
+	let Some(task) = pending.pop_front() else { return Ok(None) };
+	let bytes = task.await.map_err(|e| Error::Child(e.to_string()))??;
+	sink.write_all(&bytes)?;
+	Ok(Some(()))
+}
+
+#[derive(Deserialize)]
+struct Verbose {
+	text:     String,
+	segments: Vec<Segment>,
+}
+
+#[derive(Deserialize)]
+struct Segment {
+	start: f64,
+	end:   f64,
+	text:  String,
+}
+
+async fn transcribe(client: &reqwest::Client, path: &str) -> Result<String, Error> {
+	if &*CONFIG.stt_backend == "local" {
+		// Offline path: shell out to a locally installed whisper.cpp/whisper CLI and read its text.
+		let output = std::process::Command::new(&*CONFIG.whisper_bin)
+			.args(["-m", &CONFIG.whisper_model, "-f", path, "-nt"])
+			.output()?;
+		if !output.status.success() {
+			return Err(Error::Child(CONFIG.whisper_bin.to_string()));
+		}
+		return Ok(String::from_utf8_lossy(&output.stdout).trim().to_string());
+	}
+
+	let mut form = reqwest::multipart::Form::new()
+		.file("file", path).await?
+		.text("model", "whisper-1");
+
+	if &*CONFIG.transcript_format == "verbose" {
+		form = form
+			.text("response_format", "verbose_json")
+			.text("timestamp_granularities[]", "segment");
+	}
+
+	let resp = check_err(client.post("https://api.openai.com/v1/audio/transcriptions")
+		.header("Authorization", &*CONFIG.openai_key)
+		.multipart(form)
+		.send().await?).await?;
+
+	if &*CONFIG.transcript_format == "verbose" {
+		let verbose = resp.json::<Verbose>().await?;
+		write_srt(path, &verbose.segments)?;
+		return Ok(verbose.text);
+	}
+
+	let serde_json::Value::String(text) = resp
+		.json::<serde_json::Value>().await?
+		.get_mut("text").ok_or(Error::Shape("text"))?.take()
+	else { return Err(Error::Shape("text")); };
+	Ok(text)
+}
+
+/// Write the whisper segments out as an `.srt` sidecar next to the recorded audio file.
+fn write_srt(audio_path: &str, segments: &[Segment]) -> std::io::Result<()> {
+	use std::io::Write;
+
+	let srt = std::path::Path::new(audio_path).with_extension("srt");
+	let mut f = std::io::BufWriter::new(std::fs::File::create(srt)?);
+	for (i, seg) in segments.iter().enumerate() {
+		writeln!(f, "{}", i + 1)?;
+		writeln!(f, "{} --> {}", srt_time(seg.start), srt_time(seg.end))?;
+		writeln!(f, "{}\n", seg.text.trim())?;
+	}
+	f.flush()
+}
+
+fn srt_time(secs: f64) -> String {
+	let ms    = (secs * 1000.0).round() as u64;
+	let (s, ms) = (ms / 1000, ms % 1000);
+	let (m, s)  = (s / 60, s % 60);
+	let (h, m)  = (m / 60, m % 60);
+	format!("{h:02}:{m:02}:{s:02},{ms:03}")
+}
+
+async fn chat(client: &reqwest::Client, body: String) -> Result<String, Error> {
+	let serde_json::Value::String(content) =
+		check_err(client.post("https://api.openai.com/v1/chat/completions")
+			.header("Authorization", &*CONFIG.openai_key)
+			.header("Content-Type", "application/json")
+			.body(body)
+			.send().await?).await?
+			.json::<serde_json::Value>().await?
+			.get_mut("choices").ok_or(Error::Shape("choices"))?.take()
+			.get_mut(0).ok_or(Error::Shape("choices[0]"))?.take()
+			.get_mut("message").ok_or(Error::Shape("message"))?.take()
+			.get_mut("content").ok_or(Error::Shape("content"))?.take()
+		else { return Err(Error::Shape("content")); };
+	Ok(content)
+}
+
+async fn synthesize(client: &reqwest::Client, style: Option<&str>, text: String) -> Result<Vec<u8>, Error> {
+	Ok(match &*CONFIG.tts_backend {
+		"openai" => check_err(client.post("https://api.openai.com/v1/audio/speech")
+			.header("Authorization", &*CONFIG.openai_key)
+			.header("Content-Type", "application/json")
+			.body(serde_json::json!({
+				"model":           "tts-1",
+				"voice":           if CONFIG.azure_voice.is_empty() { "alloy" } else { &*CONFIG.azure_voice },
+				"input":           text,
+				"response_format": "mp3",
+			}).to_string())
+			.send().await?).await?
+			.bytes().await?.to_vec(),
+		_ => check_err(client.post(format!("https://{}.tts.speech.microsoft.com/cognitiveservices/v1", &*CONFIG.azure_region))
 			.header("Ocp-Apim-Subscription-Key", &*CONFIG.azure_key)
 			.header("Content-Type", "application/ssml+xml")
 			.header("X-Microsoft-OutputFormat", "audio-48khz-96kbitrate-mono-mp3")
 			.header("User-Agent", "curl")
 			.body(format!(" <speak version='1.0' xmlns='http://www.w3.org/2001/10/synthesis' xmlns:mstts='http://www.w3.org/2001/mstts' xml:lang='en-US'><voice name='{}'>{}</voice></speak>",
 				if CONFIG.azure_voice.is_empty() { "en-US-JasonNeural" } else { &*CONFIG.azure_voice },
-				match resp.starts_with(":").then(|| resp.find(" ").unwrap_or(resp.len()) + 1) {
-					None      => resp,
-					Some(pos) => format!("<mstts:express-as style='{}'>{}</mstts:express-as>", &resp[1..pos], &resp[pos+1..]),
+				match style {
+					None     => text,
+					Some(st) => format!("<mstts:express-as style='{}'>{}</mstts:express-as>", st, text),
 				}))
-			.send().await?).await
-			.bytes().await?;
-
-		std::process::Command::new("mpv")
-			.args(["-", "--no-terminal"])
-			.stdin(std::process::Stdio::piped())
-			.spawn()?.stdin.unwrap()
-			.write_all(&resp)?;
-	}
+			.send().await?).await?
+			.bytes().await?.to_vec(),
+	})
 }
 
-async fn check_err(thing: reqwest::Response) -> reqwest::Response {
-	match thing.error_for_status_ref() {
-		Ok(_) => thing,
-		Err(e) => panic!("Error: {e}, {}", String::from_utf8_lossy(&thing.bytes().await.unwrap())),
+async fn check_err(resp: reqwest::Response) -> Result<reqwest::Response, Error> {
+	match resp.error_for_status_ref() {
+		Ok(_)  => Ok(resp),
+		Err(e) => Err(Error::Status {
+			status: e.status().unwrap_or_default(),
+			body:   String::from_utf8_lossy(&resp.bytes().await?).into_owned(),
+		}),
 	}
 }
 